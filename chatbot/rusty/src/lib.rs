@@ -1,8 +1,24 @@
-use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use serde::{Serialize, Deserialize};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder as AutoBuilder;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch, Mutex, Notify};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+
+mod tls;
+mod transport;
+mod ws;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ChatMessage {
@@ -10,14 +26,75 @@ pub struct ChatMessage {
     pub message: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAIMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAIChatRequest {
+    pub model: String,
+    pub messages: Vec<OpenAIMessage>,
+    #[serde(default)]
+    pub stream: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OpenAIChatChoice {
+    pub index: u32,
+    pub message: OpenAIMessage,
+    pub finish_reason: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OpenAIChatChunkChoice {
+    pub index: u32,
+    pub delta: OpenAIMessage,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OpenAIUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OpenAIChatResponse {
+    pub id: String,
+    pub object: String,
+    pub model: String,
+    pub choices: Vec<OpenAIChatChoice>,
+    pub usage: OpenAIUsage,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OpenAIChatChunk {
+    pub id: String,
+    pub object: String,
+    pub model: String,
+    pub choices: Vec<OpenAIChatChunkChoice>,
+}
+
+type ResponseBody = BoxBody<Bytes, Infallible>;
+
 pub struct Rusty {
     messages: Arc<Mutex<Vec<ChatMessage>>>,
+    tools: Arc<transport::Transport>,
+    /// Shared secret required (via the `X-Rusty-Tool-Token` header) before a
+    /// `/run` chat message is allowed to reach the command runner. Read from
+    /// `RUSTY_TOOL_TOKEN`; if unset, `/run` is refused for every request.
+    tool_token: Option<Arc<String>>,
 }
 
 impl Rusty {
     pub fn new() -> Self {
         Rusty {
             messages: Arc::new(Mutex::new(Vec::new())),
+            tools: transport::spawn_command_runner(),
+            tool_token: std::env::var("RUSTY_TOOL_TOKEN").ok().map(Arc::new),
         }
     }
 
@@ -25,55 +102,617 @@ impl Rusty {
         let listener = TcpListener::bind(addr).await?;
         println!("Rusty chatbot listening on: {}", addr);
 
+        let mut shutdown = Shutdown::new();
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let stream = match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(err) => {
+                            eprintln!("accept error: {}", err);
+                            continue;
+                        }
+                    };
+                    let messages = Arc::clone(&self.messages);
+                    let tools = Arc::clone(&self.tools);
+                    let tool_token = self.tool_token.clone();
+                    let guard = shutdown.guard();
+                    let signal = shutdown.signal();
+
+                    tokio::spawn(handle_connection(TokioIo::new(stream), messages, tools, tool_token, guard, signal));
+                }
+                _ = shutdown.signalled() => {
+                    println!("Shutdown signal received, draining in-flight connections...");
+                    break;
+                }
+            }
+        }
+
+        shutdown.drained().await;
+        println!("Rusty chatbot shut down cleanly");
+        Ok(())
+    }
+
+    /// Like `run`, but terminates TLS itself (via `rustls`) instead of
+    /// relying on a reverse proxy in front of the listener. Callers without
+    /// a cert/key pair to configure should call `run` instead.
+    pub async fn run_tls(&self, addr: &str, cert: &str, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let acceptor = tls::build_acceptor(cert, key)?;
+        let listener = TcpListener::bind(addr).await?;
+        println!("Rusty chatbot listening on: {} (TLS)", addr);
+
+        let mut shutdown = Shutdown::new();
+
         loop {
-            let (mut stream, _) = listener.accept().await?;
-            let messages = Arc::clone(&self.messages);
-
-            tokio::spawn(async move {
-                let mut buffer = [0; 1024];
-                let n = stream.read(&mut buffer).await.unwrap();
-                let request = String::from_utf8_lossy(&buffer[..n]);
-
-                if request.starts_with("POST /api/chat") {
-                    // Handle POST request
-                    let body_start = request.find("\r\n\r\n").map(|i| i + 4).unwrap_or(0);
-                    let body = &request[body_start..];
-                    match serde_json::from_str::<ChatMessage>(body) {
-                        Ok(chat_message) => {
-                            let response = process_message(&chat_message).await;
-                            let response_json = serde_json::to_string(&response).unwrap();
-                            let response = format!(
-                                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nAccess-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: POST, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\nContent-Length: {}\r\n\r\n{}",
-                                response_json.len(),
-                                response_json
-                            );
-                            stream.write_all(response.as_bytes()).await.unwrap();
-                        },
-                        Err(_) => {
-                            // Handle JSON parsing error
-                            let error_response = format!(
-                                "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nAccess-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: POST, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\nContent-Length: 22\r\n\r\nInvalid JSON payload"
-                            );
-                            stream.write_all(error_response.as_bytes()).await.unwrap();
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let stream = match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(err) => {
+                            eprintln!("accept error: {}", err);
+                            continue;
+                        }
+                    };
+                    let acceptor = acceptor.clone();
+                    let messages = Arc::clone(&self.messages);
+                    let tools = Arc::clone(&self.tools);
+                    let tool_token = self.tool_token.clone();
+                    let guard = shutdown.guard();
+                    let signal = shutdown.signal();
+
+                    tokio::spawn(async move {
+                        match acceptor.accept(stream).await {
+                            Ok(tls_stream) => {
+                                handle_connection(TokioIo::new(tls_stream), messages, tools, tool_token, guard, signal).await;
+                            }
+                            Err(err) => eprintln!("TLS handshake failed: {}", err),
                         }
-                    }
-                } else if request.starts_with("OPTIONS /api/chat") {
-                    // Handle OPTIONS request for CORS
-                    let response = "HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: POST, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\nContent-Length: 0\r\n\r\n";
-                    stream.write_all(response.as_bytes()).await.unwrap();
-                } else {
-                    // Handle other requests (e.g., GET requests)
-                    let response = "HTTP/1.1 405 Method Not Allowed\r\nContent-Type: text/plain\r\nAccess-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: POST, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\nContent-Length: 29\r\n\r\nMethod not allowed for this route";
-                    stream.write_all(response.as_bytes()).await.unwrap();
+                    });
                 }
-            });
+                _ = shutdown.signalled() => {
+                    println!("Shutdown signal received, draining in-flight connections...");
+                    break;
+                }
+            }
         }
+
+        shutdown.drained().await;
+        println!("Rusty chatbot shut down cleanly");
+        Ok(())
     }
 }
 
-async fn process_message(message: &ChatMessage) -> ChatMessage {
+/// Minimal `tokio-graceful`-style shutdown: a watch channel fires when
+/// Ctrl-C is received, and a guard per in-flight connection lets `run`
+/// wait for all of them to finish before returning.
+struct Shutdown {
+    triggered: watch::Receiver<bool>,
+    in_flight: Arc<AtomicUsize>,
+    drained: Arc<Notify>,
+}
+
+impl Shutdown {
+    fn new() -> Self {
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            let _ = tx.send(true);
+        });
+
+        Shutdown {
+            triggered: rx,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            drained: Arc::new(Notify::new()),
+        }
+    }
+
+    fn guard(&self) -> ShutdownGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        ShutdownGuard {
+            in_flight: Arc::clone(&self.in_flight),
+            drained: Arc::clone(&self.drained),
+        }
+    }
+
+    /// A receiver `handle_connection` can poll alongside its connection
+    /// future to learn when to start winding the connection down.
+    fn signal(&self) -> watch::Receiver<bool> {
+        self.triggered.clone()
+    }
+
+    async fn signalled(&mut self) {
+        let _ = self.triggered.changed().await;
+    }
+
+    async fn drained(&self) {
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            self.drained.notified().await;
+        }
+    }
+}
+
+struct ShutdownGuard {
+    in_flight: Arc<AtomicUsize>,
+    drained: Arc<Notify>,
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        if self.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.drained.notify_waiters();
+        }
+    }
+}
+
+async fn handle_connection<IO>(
+    io: IO,
+    messages: Arc<Mutex<Vec<ChatMessage>>>,
+    tools: Arc<transport::Transport>,
+    tool_token: Option<Arc<String>>,
+    guard: ShutdownGuard,
+    mut shutdown_signal: watch::Receiver<bool>,
+) where
+    IO: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let service = service_fn(move |req| {
+        let messages = Arc::clone(&messages);
+        let tools = Arc::clone(&tools);
+        let tool_token = tool_token.clone();
+        async move { route(req, messages, tools, tool_token).await }
+    });
+
+    let conn = AutoBuilder::new(TokioExecutor::new()).serve_connection(io, service);
+    let mut conn = std::pin::pin!(conn);
+
+    loop {
+        tokio::select! {
+            result = conn.as_mut() => {
+                if let Err(err) = result {
+                    eprintln!("connection error: {}", err);
+                }
+                break;
+            }
+            _ = shutdown_signal.changed() => {
+                // Tell the connection (including long-lived SSE/WebSocket
+                // ones) to wind down instead of waiting for it to finish on
+                // its own, then keep polling it to completion above.
+                conn.as_mut().graceful_shutdown();
+            }
+        }
+    }
+
+    drop(guard);
+}
+
+async fn route(
+    req: Request<Incoming>,
+    messages: Arc<Mutex<Vec<ChatMessage>>>,
+    tools: Arc<transport::Transport>,
+    tool_token: Option<Arc<String>>,
+) -> Result<Response<ResponseBody>, Infallible> {
+    let wants_stream = req
+        .headers()
+        .get(hyper::header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.contains("text/event-stream"))
+        .unwrap_or(false);
+
+    match (req.method(), req.uri().path()) {
+        (&Method::OPTIONS, "/api/chat")
+        | (&Method::OPTIONS, "/api/chat/stream")
+        | (&Method::OPTIONS, "/v1/chat/completions") => Ok(cors_preflight()),
+        (&Method::POST, "/api/chat/stream") => handle_chat_stream(req).await,
+        (&Method::POST, "/api/chat") if wants_stream => handle_chat_stream(req).await,
+        (&Method::POST, "/api/chat") => handle_chat(req, tools, tool_token).await,
+        (&Method::POST, "/v1/chat/completions") => handle_completions(req, messages).await,
+        (&Method::GET, "/ws") => handle_ws_upgrade(req, messages).await,
+        _ => Ok(method_not_allowed()),
+    }
+}
+
+async fn handle_ws_upgrade(
+    mut req: Request<Incoming>,
+    messages: Arc<Mutex<Vec<ChatMessage>>>,
+) -> Result<Response<ResponseBody>, Infallible> {
+    if !hyper_tungstenite::is_upgrade_request(&req) {
+        return Ok(bad_request());
+    }
+
+    let (response, websocket) = match hyper_tungstenite::upgrade(&mut req, None) {
+        Ok(upgrade) => upgrade,
+        Err(_) => return Ok(bad_request()),
+    };
+
+    tokio::spawn(async move {
+        match websocket.await {
+            Ok(ws_stream) => {
+                let ctx = ws::Ctx { messages };
+                ws::handle_socket(ws_stream, ctx, Arc::new(ws::ChatService)).await;
+            }
+            Err(err) => eprintln!("websocket upgrade failed: {}", err),
+        }
+    });
+
+    let (parts, _) = response.into_parts();
+    Ok(Response::from_parts(parts, empty_body()))
+}
+
+async fn handle_chat(
+    req: Request<Incoming>,
+    tools: Arc<transport::Transport>,
+    tool_token: Option<Arc<String>>,
+) -> Result<Response<ResponseBody>, Infallible> {
+    let authorized = tool_token
+        .as_deref()
+        .zip(req.headers().get("x-rusty-tool-token").and_then(|v| v.to_str().ok()))
+        .map(|(expected, provided)| provided == expected.as_str())
+        .unwrap_or(false);
+
+    let bytes = match req.into_body().collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => return Ok(bad_request()),
+    };
+
+    match serde_json::from_slice::<ChatMessage>(&bytes) {
+        Ok(chat_message) => {
+            let reply = process_message(&chat_message, &tools, authorized).await;
+            Ok(json_response(&reply))
+        }
+        Err(_) => Ok(bad_request()),
+    }
+}
+
+async fn handle_chat_stream(req: Request<Incoming>) -> Result<Response<ResponseBody>, Infallible> {
+    let bytes = match req.into_body().collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => return Ok(bad_request()),
+    };
+
+    let chat_message = match serde_json::from_slice::<ChatMessage>(&bytes) {
+        Ok(message) => message,
+        Err(_) => return Ok(bad_request()),
+    };
+
+    let mut deltas = process_message_stream(chat_message);
+    let (tx, rx) = mpsc::unbounded_channel::<String>();
+
+    tokio::spawn(async move {
+        while let Some(delta) = deltas.recv().await {
+            let delta_json = serde_json::to_string(&delta).unwrap();
+            if tx.send(format!("data: {}\n\n", delta_json)).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send("data: [DONE]\n\n".to_string());
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(stream_body(rx))
+        .unwrap())
+}
+
+async fn handle_completions(
+    req: Request<Incoming>,
+    messages: Arc<Mutex<Vec<ChatMessage>>>,
+) -> Result<Response<ResponseBody>, Infallible> {
+    let bytes = match req.into_body().collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => return Ok(bad_request()),
+    };
+
+    let completion_request = match serde_json::from_slice::<OpenAIChatRequest>(&bytes) {
+        Ok(request) => request,
+        Err(_) => return Ok(bad_request()),
+    };
+
+    let history = {
+        let mut messages = messages.lock().await;
+        messages.extend(completion_request.messages.iter().map(|m| ChatMessage {
+            user: m.role.clone(),
+            message: m.content.clone(),
+        }));
+        messages.clone()
+    };
+
+    let reply = process_conversation(&history).await;
+    {
+        let mut messages = messages.lock().await;
+        messages.push(reply.clone());
+    }
+
+    if completion_request.stream {
+        let chunk = OpenAIChatChunk {
+            id: completion_id(),
+            object: "chat.completion.chunk".to_string(),
+            model: completion_request.model.clone(),
+            choices: vec![OpenAIChatChunkChoice {
+                index: 0,
+                delta: OpenAIMessage {
+                    role: "assistant".to_string(),
+                    content: reply.message,
+                },
+                finish_reason: Some("stop".to_string()),
+            }],
+        };
+
+        let (tx, rx) = mpsc::unbounded_channel::<String>();
+        let _ = tx.send(format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap()));
+        let _ = tx.send("data: [DONE]\n\n".to_string());
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(stream_body(rx))
+            .unwrap())
+    } else {
+        let prompt_tokens: usize = history
+            .iter()
+            .map(|m| m.message.split_whitespace().count())
+            .sum();
+        let completion_tokens = reply.message.split_whitespace().count();
+
+        let response = OpenAIChatResponse {
+            id: completion_id(),
+            object: "chat.completion".to_string(),
+            model: completion_request.model,
+            choices: vec![OpenAIChatChoice {
+                index: 0,
+                message: OpenAIMessage {
+                    role: "assistant".to_string(),
+                    content: reply.message,
+                },
+                finish_reason: "stop".to_string(),
+            }],
+            usage: OpenAIUsage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            },
+        };
+
+        Ok(json_response(&response))
+    }
+}
+
+fn json_response(value: &impl Serialize) -> Response<ResponseBody> {
+    let body = serde_json::to_vec(value).unwrap();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "POST, OPTIONS")
+        .header("Access-Control-Allow-Headers", "Content-Type")
+        .body(full_body(body))
+        .unwrap()
+}
+
+fn cors_preflight() -> Response<ResponseBody> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "POST, OPTIONS")
+        .header("Access-Control-Allow-Headers", "Content-Type")
+        .body(empty_body())
+        .unwrap()
+}
+
+fn bad_request() -> Response<ResponseBody> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header("Content-Type", "text/plain")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(full_body("Invalid JSON payload"))
+        .unwrap()
+}
+
+fn method_not_allowed() -> Response<ResponseBody> {
+    Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .header("Content-Type", "text/plain")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(full_body("Method not allowed for this route"))
+        .unwrap()
+}
+
+fn full_body(chunk: impl Into<Bytes>) -> ResponseBody {
+    Full::new(chunk.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+fn empty_body() -> ResponseBody {
+    Empty::<Bytes>::new().map_err(|never| match never {}).boxed()
+}
+
+fn stream_body(rx: mpsc::UnboundedReceiver<String>) -> ResponseBody {
+    let frames = UnboundedReceiverStream::new(rx).map(|chunk| Ok(Frame::data(Bytes::from(chunk))));
+    StreamBody::new(frames).boxed()
+}
+
+async fn process_message(message: &ChatMessage, tools: &Arc<transport::Transport>, authorized: bool) -> ChatMessage {
+    if let Some(command) = message.message.strip_prefix("/run ") {
+        if !authorized {
+            return ChatMessage {
+                user: "Rusty".to_string(),
+                message: "Command execution requires a valid X-Rusty-Tool-Token header.".to_string(),
+            };
+        }
+        return run_tool_command(tools, command).await;
+    }
+
     ChatMessage {
         user: "Rusty".to_string(),
         message: format!("You said: {}", message.message),
     }
 }
+
+/// Dispatches a `/run <command>` chat message to the Redox command runner
+/// over the framed transport and turns its response into a reply.
+async fn run_tool_command(tools: &Arc<transport::Transport>, command: &str) -> ChatMessage {
+    let arguments = serde_json::json!({ "command": command });
+
+    match tools.request("exec", arguments).await {
+        Ok(response) if response.success => ChatMessage {
+            user: "Rusty".to_string(),
+            message: response
+                .body
+                .as_ref()
+                .and_then(|body| body.get("output"))
+                .and_then(|output| output.as_str())
+                .unwrap_or_default()
+                .to_string(),
+        },
+        Ok(response) => ChatMessage {
+            user: "Rusty".to_string(),
+            message: format!("Command failed: {}", response.message.unwrap_or_default()),
+        },
+        Err(_) => ChatMessage {
+            user: "Rusty".to_string(),
+            message: "Command runner is unavailable.".to_string(),
+        },
+    }
+}
+
+/// Replies using the full conversation history rather than just the latest
+/// turn, so the bot can be aware of how many messages have come before.
+async fn process_conversation(history: &[ChatMessage]) -> ChatMessage {
+    let last_user_message = history
+        .iter()
+        .rev()
+        .find(|m| m.user == "user")
+        .map(|m| m.message.clone())
+        .unwrap_or_default();
+
+    ChatMessage {
+        user: "assistant".to_string(),
+        message: format!(
+            "You said: {} (with {} messages of context)",
+            last_user_message,
+            history.len()
+        ),
+    }
+}
+
+fn completion_id() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    format!("chatcmpl-{}", COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Like `process_message`, but yields the reply incrementally as a stream of
+/// `ChatMessage` deltas instead of a single fully-formed message. Every
+/// message is echoed; `/run` commands are not sent to the command runner.
+pub(crate) fn process_message_stream(message: ChatMessage) -> mpsc::UnboundedReceiver<ChatMessage> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let reply = format!("You said: {}", message.message);
+
+    tokio::spawn(async move {
+        for word in reply.split_whitespace() {
+            if tx
+                .send(ChatMessage {
+                    user: "Rusty".to_string(),
+                    message: format!("{} ", word),
+                })
+                .is_err()
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn drained_waits_for_every_guard_to_drop() {
+        let shutdown = Shutdown::new();
+        let guard_a = shutdown.guard();
+        let guard_b = shutdown.guard();
+
+        let waiter = tokio::spawn(async move {
+            shutdown.drained().await;
+        });
+        // `guard_b` is still held, so `drained()` must not have resolved yet.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        drop(guard_a);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished(), "drained() resolved before the last guard was dropped");
+
+        drop(guard_b);
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("drained() should resolve once every guard has dropped")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn signalled_resolves_once_the_watch_channel_fires() {
+        let (tx, rx) = watch::channel(false);
+        let mut shutdown = Shutdown {
+            triggered: rx,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            drained: Arc::new(Notify::new()),
+        };
+
+        let waiter = tokio::spawn(async move {
+            shutdown.signalled().await;
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        tx.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("signalled() should resolve once the shutdown channel fires")
+            .unwrap();
+    }
+
+    /// A keep-alive HTTP/1.1 connection that never closes on its own is
+    /// exactly the case `graceful_shutdown()` exists to handle: without it,
+    /// `handle_connection` would wait on `serve_connection` forever.
+    #[tokio::test]
+    async fn handle_connection_drains_an_idle_keep_alive_connection_on_shutdown_signal() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (server_stream, _) = listener.accept().await.unwrap();
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let tools = transport::spawn_command_runner();
+        let (tx, rx) = watch::channel(false);
+        let in_flight = Arc::new(AtomicUsize::new(1));
+        let drained = Arc::new(Notify::new());
+        let guard = ShutdownGuard { in_flight: Arc::clone(&in_flight), drained: Arc::clone(&drained) };
+
+        let connection = tokio::spawn(handle_connection(TokioIo::new(server_stream), messages, tools, None, guard, rx));
+
+        // Let the connection start serving and sit idle, as a real keep-alive
+        // client would between requests.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!connection.is_finished());
+
+        tx.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), connection)
+            .await
+            .expect("handle_connection should drain instead of hanging on an idle keep-alive connection")
+            .unwrap();
+
+        let _ = client.shutdown().await;
+    }
+}
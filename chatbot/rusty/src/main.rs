@@ -3,6 +3,11 @@ use rusty::Rusty;
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let rusty = Rusty::new();
-    rusty.run("0.0.0.0:80").await?;
+
+    match (std::env::var("RUSTY_TLS_CERT"), std::env::var("RUSTY_TLS_KEY")) {
+        (Ok(cert), Ok(key)) => rusty.run_tls("0.0.0.0:443", &cert, &key).await?,
+        _ => rusty.run("0.0.0.0:80").await?,
+    }
+
     Ok(())
 }
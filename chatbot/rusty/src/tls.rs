@@ -0,0 +1,30 @@
+//! TLS setup for `Rusty::run_tls`: load a PEM cert/key pair and build the
+//! `rustls` server config used to terminate HTTPS directly, without a
+//! reverse proxy in front of the chatbot.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use rustls_pemfile::{certs, pkcs8_private_keys};
+use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+pub fn build_acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, Box<dyn std::error::Error>> {
+    let mut cert_reader = BufReader::new(File::open(cert_path)?);
+    let mut key_reader = BufReader::new(File::open(key_path)?);
+
+    let cert_chain = certs(&mut cert_reader).collect::<Result<Vec<_>, _>>()?;
+    let mut keys = pkcs8_private_keys(&mut key_reader).collect::<Result<Vec<_>, _>>()?;
+    let key: PrivateKeyDer<'static> = keys
+        .pop()
+        .ok_or("no PKCS#8 private key found in key file")?
+        .into();
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,287 @@
+//! Length-prefixed framed transport, modeled on the Debug Adapter Protocol:
+//! each frame is a `Content-Length: <n>\r\n\r\n` header followed by exactly
+//! `n` bytes of JSON. Used to talk to backend tools (like the Redox command
+//! runner) over a duplex byte stream instead of a one-shot function call.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use redox_vm::execute_command;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Request {
+    pub seq: u64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub command: String,
+    pub arguments: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Response {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub request_seq: u64,
+    pub success: bool,
+    #[serde(default)]
+    pub body: Option<Value>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    Disconnected,
+}
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Response, TransportError>>>>>;
+
+/// A framed request/response transport. Requests are dispatched with a
+/// client-chosen `seq`; the matching response is correlated back to the
+/// caller's `oneshot` by `request_seq`, so many requests can be in flight
+/// on the same stream at once.
+pub struct Transport {
+    next_seq: AtomicU64,
+    pending: Pending,
+    outbound: mpsc::UnboundedSender<Request>,
+}
+
+impl Transport {
+    fn spawn<R, W>(reader: R, writer: W) -> Arc<Self>
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, rx) = mpsc::unbounded_channel::<Request>();
+
+        tokio::spawn(write_loop(writer, rx));
+        tokio::spawn(read_loop(reader, Arc::clone(&pending)));
+
+        Arc::new(Transport {
+            next_seq: AtomicU64::new(1),
+            pending,
+            outbound: tx,
+        })
+    }
+
+    pub async fn request(&self, command: &str, arguments: Value) -> Result<Response, TransportError> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(seq, tx);
+
+        let request = Request {
+            seq,
+            kind: "request".to_string(),
+            command: command.to_string(),
+            arguments,
+        };
+
+        if self.outbound.send(request).is_err() {
+            self.pending.lock().await.remove(&seq);
+            return Err(TransportError::Disconnected);
+        }
+
+        rx.await.unwrap_or(Err(TransportError::Disconnected))
+    }
+}
+
+async fn write_loop<W>(mut writer: W, mut outbound: mpsc::UnboundedReceiver<Request>)
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(request) = outbound.recv().await {
+        if write_frame(&mut writer, &request).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_loop<R>(mut reader: R, pending: Pending)
+where
+    R: AsyncBufRead + Unpin,
+{
+    loop {
+        match read_frame::<_, Response>(&mut reader).await {
+            Ok(Some(response)) => {
+                if let Some(tx) = pending.lock().await.remove(&response.request_seq) {
+                    let _ = tx.send(Ok(response));
+                }
+            }
+            Ok(None) | Err(_) => break,
+        }
+    }
+
+    for (_, tx) in pending.lock().await.drain() {
+        let _ = tx.send(Err(TransportError::Disconnected));
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, value: &impl Serialize) -> std::io::Result<()> {
+    let body = serde_json::to_vec(value).expect("frame payload is always serializable");
+    let header = format!("Content-Length: {}\r\n\r\n", body.len());
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await
+}
+
+async fn read_frame<R, T>(reader: &mut R) -> std::io::Result<Option<T>>
+where
+    R: AsyncBufRead + Unpin,
+    T: for<'de> Deserialize<'de>,
+{
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length: ") {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(len) = content_length else {
+        return Ok(None);
+    };
+
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    Ok(serde_json::from_slice(&body).ok())
+}
+
+/// Spawns the command-runner side of the transport in-process (paired with
+/// the client half via a duplex pipe) and returns the client `Transport`
+/// that chat handling dispatches `/run` commands through.
+pub fn spawn_command_runner() -> Arc<Transport> {
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let (client_read, client_write) = tokio::io::split(client_io);
+    let (server_read, server_write) = tokio::io::split(server_io);
+
+    tokio::spawn(serve_command_runner(BufReader::new(server_read), server_write));
+
+    Transport::spawn(BufReader::new(client_read), client_write)
+}
+
+async fn serve_command_runner<R, W>(mut reader: R, mut writer: W)
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let request = match read_frame::<_, Request>(&mut reader).await {
+            Ok(Some(request)) => request,
+            Ok(None) | Err(_) => return,
+        };
+
+        let response = if request.command == "exec" {
+            let command = request
+                .arguments
+                .get("command")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let result = execute_command(command).await;
+
+            match result.error {
+                Some(error) => Response {
+                    kind: "response".to_string(),
+                    request_seq: request.seq,
+                    success: false,
+                    body: None,
+                    message: Some(error),
+                },
+                None => Response {
+                    kind: "response".to_string(),
+                    request_seq: request.seq,
+                    success: true,
+                    body: Some(serde_json::json!({ "output": result.output })),
+                    message: None,
+                },
+            }
+        } else {
+            Response {
+                kind: "response".to_string(),
+                request_seq: request.seq,
+                success: false,
+                body: None,
+                message: Some(format!("unknown command: {}", request.command)),
+            }
+        };
+
+        if write_frame(&mut writer, &response).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Payload {
+        value: u64,
+    }
+
+    #[tokio::test]
+    async fn write_frame_round_trips_through_read_frame() {
+        let (a, b) = tokio::io::duplex(1024);
+        let (_, mut writer) = tokio::io::split(a);
+        let (reader, _) = tokio::io::split(b);
+        let mut reader = BufReader::new(reader);
+
+        write_frame(&mut writer, &Payload { value: 42 }).await.unwrap();
+
+        let payload: Payload = read_frame(&mut reader).await.unwrap().unwrap();
+        assert_eq!(payload, Payload { value: 42 });
+    }
+
+    #[tokio::test]
+    async fn read_frame_returns_none_on_clean_eof() {
+        let (a, b) = tokio::io::duplex(1024);
+        drop(a);
+        let (reader, _) = tokio::io::split(b);
+        let mut reader = BufReader::new(reader);
+
+        let payload: Option<Payload> = read_frame(&mut reader).await.unwrap();
+        assert!(payload.is_none());
+    }
+
+    #[tokio::test]
+    async fn read_frame_returns_none_without_content_length_header() {
+        let (a, b) = tokio::io::duplex(1024);
+        let (_, mut writer) = tokio::io::split(a);
+        let (reader, _) = tokio::io::split(b);
+        let mut reader = BufReader::new(reader);
+
+        writer.write_all(b"X-Garbage: yes\r\n\r\n").await.unwrap();
+        drop(writer);
+
+        let payload: Option<Payload> = read_frame(&mut reader).await.unwrap();
+        assert!(payload.is_none());
+    }
+
+    #[tokio::test]
+    async fn read_frame_errors_on_eof_mid_body() {
+        let (a, b) = tokio::io::duplex(1024);
+        let (_, mut writer) = tokio::io::split(a);
+        let (reader, _) = tokio::io::split(b);
+        let mut reader = BufReader::new(reader);
+
+        writer.write_all(b"Content-Length: 10\r\n\r\n12345").await.unwrap();
+        drop(writer);
+
+        let result: std::io::Result<Option<Payload>> = read_frame(&mut reader).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! WebSocket RPC subsystem: a generic request/response `Service` multiplexed
+//! over a single socket, keyed by a client-chosen request id so several
+//! requests can be in flight (and stream back multiple frames each) at once.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use hyper_tungstenite::tungstenite::Message;
+use hyper_tungstenite::WebSocketStream;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_stream::Stream;
+
+use crate::ChatMessage;
+
+/// Shared state handed to every `Service::serve` call.
+#[derive(Clone)]
+pub struct Ctx {
+    pub messages: Arc<Mutex<Vec<ChatMessage>>>,
+}
+
+/// A request/response service that can answer a single request with a
+/// stream of responses (e.g. streamed chat deltas) rather than just one.
+pub trait Service: Send + Sync + 'static {
+    type Req: DeserializeOwned + Send;
+    type Resp: Serialize + Send;
+    type Error: Serialize + Send;
+
+    fn serve(
+        &self,
+        ctx: Ctx,
+        req: Self::Req,
+    ) -> Pin<Box<dyn Stream<Item = Result<Self::Resp, Self::Error>> + Send>>;
+}
+
+/// Exposes the chatbot's streaming reply as a `Service`, so the same
+/// word-by-word deltas that power the SSE endpoint can be multiplexed over
+/// the WebSocket transport.
+pub struct ChatService;
+
+impl Service for ChatService {
+    type Req = ChatMessage;
+    type Resp = ChatMessage;
+    type Error = String;
+
+    fn serve(
+        &self,
+        _ctx: Ctx,
+        req: Self::Req,
+    ) -> Pin<Box<dyn Stream<Item = Result<ChatMessage, String>> + Send>> {
+        let deltas = crate::process_message_stream(req);
+        Box::pin(tokio_stream::wrappers::UnboundedReceiverStream::new(deltas).map(Ok))
+    }
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    id: u64,
+    #[serde(flatten)]
+    payload: T,
+}
+
+#[derive(Serialize)]
+struct OutEnvelope<R, E> {
+    id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resp: Option<R>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<E>,
+}
+
+struct ActiveRequest {
+    id: u64,
+    stream: Pin<Box<dyn Stream<Item = Result<ChatMessage, String>> + Send>>,
+}
+
+/// Items pulled from a single request's response stream before yielding the
+/// turn to the next request, so one chatty client request can't starve the
+/// others sharing this socket.
+const FAIRNESS_BUDGET: usize = 64;
+
+/// Ids of recently-completed requests, kept so a client can't reuse one for
+/// a new request (which would otherwise dispatch normally, silently
+/// colliding with the finished request's id in any client-side bookkeeping).
+/// Once this many requests have finished, the oldest ids are forgotten so
+/// this doesn't grow forever on a long-lived socket.
+const FINISHED_ID_CAP: usize = 256;
+
+pub async fn handle_socket<S, IO>(ws: WebSocketStream<IO>, ctx: Ctx, service: Arc<S>)
+where
+    S: Service<Req = ChatMessage, Resp = ChatMessage, Error = String>,
+    IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (mut sink, mut source) = ws.split();
+    let mut active: VecDeque<ActiveRequest> = VecDeque::new();
+    let mut finished_ids: VecDeque<u64> = VecDeque::new();
+
+    loop {
+        // With no requests in flight there's nothing to round-robin, so block
+        // for the next frame. Otherwise only take a frame if one is already
+        // available, falling straight through to give every active request
+        // its turn instead of starving them waiting on the client.
+        let incoming = if active.is_empty() {
+            Some(source.next().await)
+        } else {
+            tokio::select! {
+                biased;
+                incoming = source.next() => Some(incoming),
+                _ = std::future::ready(()) => None,
+            }
+        };
+
+        match incoming {
+            Some(Some(Ok(Message::Text(text)))) => {
+                match serde_json::from_str::<Envelope<ChatMessage>>(&text) {
+                    Ok(envelope)
+                        if finished_ids.contains(&envelope.id)
+                            || active.iter().any(|a| a.id == envelope.id) =>
+                    {
+                        let frame = OutEnvelope::<ChatMessage, String> {
+                            id: envelope.id,
+                            resp: None,
+                            error: Some("request id is already in use by another request".to_string()),
+                        };
+                        if sink.send(Message::text(serde_json::to_string(&frame).unwrap())).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(envelope) => {
+                        let stream = service.serve(ctx.clone(), envelope.payload);
+                        active.push_back(ActiveRequest { id: envelope.id, stream });
+                    }
+                    Err(err) => {
+                        let frame = OutEnvelope::<ChatMessage, String> {
+                            id: 0,
+                            resp: None,
+                            error: Some(format!("invalid request frame: {}", err)),
+                        };
+                        if sink.send(Message::text(serde_json::to_string(&frame).unwrap())).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            Some(Some(Ok(Message::Close(_)))) | Some(None) => return,
+            Some(Some(Err(_))) => return,
+            _ => {}
+        }
+
+        let rounds = active.len();
+        for _ in 0..rounds {
+            let Some(mut request) = active.pop_front() else { break };
+            let mut finished = false;
+
+            for _ in 0..FAIRNESS_BUDGET {
+                match request.stream.next().await {
+                    Some(Ok(resp)) => {
+                        let frame = OutEnvelope { id: request.id, resp: Some(resp), error: None };
+                        if sink.send(Message::text(serde_json::to_string(&frame).unwrap())).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Err(err)) => {
+                        let frame = OutEnvelope::<ChatMessage, String> { id: request.id, resp: None, error: Some(err) };
+                        let _ = sink.send(Message::text(serde_json::to_string(&frame).unwrap())).await;
+                        finished = true;
+                        break;
+                    }
+                    None => {
+                        finished = true;
+                        break;
+                    }
+                }
+            }
+
+            if finished {
+                finished_ids.push_back(request.id);
+                while finished_ids.len() > FINISHED_ID_CAP {
+                    finished_ids.pop_front();
+                }
+            } else {
+                active.push_back(request);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+    use tokio_tungstenite::WebSocketStream as ClientWebSocketStream;
+
+    #[derive(Deserialize, Serialize)]
+    struct InFrame {
+        id: u64,
+        #[serde(flatten)]
+        message: ChatMessage,
+    }
+
+    #[derive(Deserialize)]
+    struct OutFrame {
+        id: u64,
+        resp: Option<ChatMessage>,
+        error: Option<String>,
+    }
+
+    /// Wires up `handle_socket` against one end of an in-memory duplex pipe
+    /// and hands back a plain `tokio-tungstenite` client for the other end,
+    /// so tests can drive the RPC protocol without a real HTTP upgrade.
+    async fn test_socket() -> ClientWebSocketStream<tokio::io::DuplexStream> {
+        let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+        let server_ws = WebSocketStream::from_raw_socket(server_io, Role::Server, None).await;
+        let client_ws = ClientWebSocketStream::from_raw_socket(client_io, Role::Client, None).await;
+
+        let ctx = Ctx { messages: Arc::new(Mutex::new(Vec::new())) };
+        tokio::spawn(handle_socket(server_ws, ctx, Arc::new(ChatService)));
+
+        client_ws
+    }
+
+    async fn send(client: &mut ClientWebSocketStream<tokio::io::DuplexStream>, id: u64, message: &str) {
+        let frame = InFrame { id, message: ChatMessage { user: "tester".to_string(), message: message.to_string() } };
+        client.send(Message::text(serde_json::to_string(&frame).unwrap())).await.unwrap();
+    }
+
+    async fn recv(client: &mut ClientWebSocketStream<tokio::io::DuplexStream>) -> OutFrame {
+        let msg = client.next().await.unwrap().unwrap();
+        serde_json::from_str(&msg.into_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_an_id_reused_while_still_active() {
+        let mut client = test_socket().await;
+
+        // More words than FAIRNESS_BUDGET keep request 1 active after its
+        // first turn, which is when the server reads the second frame.
+        send(&mut client, 1, &"hello ".repeat(FAIRNESS_BUDGET)).await;
+        send(&mut client, 1, "again").await;
+
+        // The reused id is rejected instead of being dispatched as a second
+        // request under the same id; the rejection follows the deltas of
+        // request 1's first turn.
+        let rejection = loop {
+            let frame = recv(&mut client).await;
+            assert_eq!(frame.id, 1);
+            if frame.resp.is_none() {
+                break frame;
+            }
+        };
+        assert_eq!(rejection.id, 1);
+        assert!(rejection.resp.is_none());
+        assert_eq!(rejection.error.as_deref(), Some("request id is already in use by another request"));
+    }
+
+    #[tokio::test]
+    async fn rejects_an_id_reused_after_it_finished() {
+        let mut client = test_socket().await;
+
+        send(&mut client, 1, "hi").await;
+        // "You said: hi" streams back as three deltas. No frame marks the end
+        // of a request's stream, so read exactly those three.
+        for _ in 0..3 {
+            let frame = recv(&mut client).await;
+            assert_eq!(frame.id, 1);
+            assert!(frame.resp.is_some());
+        }
+
+        send(&mut client, 1, "again").await;
+        let rejection = recv(&mut client).await;
+        assert_eq!(rejection.id, 1);
+        assert_eq!(rejection.error.as_deref(), Some("request id is already in use by another request"));
+    }
+
+    #[tokio::test]
+    async fn round_robins_deltas_between_concurrently_active_requests() {
+        let mut client = test_socket().await;
+
+        // Request 1 streams 2 + 2 * FAIRNESS_BUDGET deltas, more than two
+        // turns can drain, so request 2 gets its turn while request 1 is
+        // still active. Request 2 streams 5 deltas.
+        send(&mut client, 1, &"aaa ".repeat(2 * FAIRNESS_BUDGET)).await;
+        send(&mut client, 2, "xxx yyy zzz").await;
+
+        // Both requests are active at once, so their deltas should be
+        // interleaved rather than request 2 waiting for request 1 to finish.
+        let mut seen_ids = Vec::new();
+        for _ in 0..(2 + 2 * FAIRNESS_BUDGET + 5) {
+            seen_ids.push(recv(&mut client).await.id);
+        }
+        assert!(seen_ids.contains(&1));
+        assert!(seen_ids.contains(&2));
+        let first_of_2 = seen_ids.iter().position(|&id| id == 2).unwrap();
+        let last_of_1 = seen_ids.iter().rposition(|&id| id == 1).unwrap();
+        assert!(
+            first_of_2 < last_of_1,
+            "deltas should be interleaved across requests, not fully serialized: {:?}",
+            seen_ids
+        );
+    }
+}
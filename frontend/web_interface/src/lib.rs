@@ -1,11 +1,16 @@
 use yew::prelude::*;
+use yew::html::Scope;
 use wasm_bindgen::prelude::*;
-use web_sys::HtmlInputElement;
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlInputElement, TextDecoder};
 use serde::{Serialize, Deserialize};
 use gloo::net::http::Request;
-use wasm_bindgen_futures::spawn_local;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use js_sys::Uint8Array;
 use log::error;
 
+const BASE_URL: &str = "https://24be1794-5ca5-4650-b243-5a7fe7a9d9fb-00-3209duemqx6n2.janeway.replit.dev";
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ChatMessage {
     pub user: String,
@@ -14,7 +19,10 @@ pub struct ChatMessage {
 
 pub enum Msg {
     Send,
-    Receive(ChatMessage),
+    /// One incremental delta of the in-progress reply from `/api/chat/stream`.
+    StreamDelta(String),
+    /// The stream's `[DONE]` sentinel: the reply bubble is complete.
+    StreamDone,
     UpdateInput(String),
     Error(String),
 }
@@ -22,6 +30,9 @@ pub enum Msg {
 pub struct Model {
     messages: Vec<ChatMessage>,
     input: String,
+    /// Whether the last message in `messages` is a reply bubble still being
+    /// filled in by an in-flight `StreamDelta` stream.
+    streaming: bool,
 }
 
 impl Component for Model {
@@ -32,6 +43,7 @@ impl Component for Model {
         Self {
             messages: Vec::new(),
             input: String::new(),
+            streaming: false,
         }
     }
 
@@ -44,53 +56,42 @@ impl Component for Model {
                 };
                 self.messages.push(message.clone());
                 self.input.clear();
+                self.streaming = false;
 
                 let link = ctx.link().clone();
                 spawn_local(async move {
-                    match Request::post("https://24be1794-5ca5-4650-b243-5a7fe7a9d9fb-00-3209duemqx6n2.janeway.replit.dev/api/chat")
-                        .header("Content-Type", "application/json")
-                        .json(&message)
-                    {
-                        Ok(request) => {
-                            match request.send().await {
-                                Ok(response) => {
-                                    if response.ok() {
-                                        match response.json::<ChatMessage>().await {
-                                            Ok(result) => link.send_message(Msg::Receive(result)),
-                                            Err(e) => {
-                                                error!("Failed to parse response: {}", e);
-                                                link.send_message(Msg::Error("Failed to parse response from server.".to_string()));
-                                            }
-                                        }
-                                    } else {
-                                        error!("Server error: {}", response.status());
-                                        link.send_message(Msg::Error(format!("Server error: {}", response.status())));
-                                    }
-                                },
-                                Err(e) => {
-                                    error!("Failed to send message: {}", e);
-                                    link.send_message(Msg::Error("Failed to send message. Please try again.".to_string()));
-                                },
-                            }
-                        },
-                        Err(e) => {
-                            error!("Failed to create request: {}", e);
-                            link.send_message(Msg::Error("Failed to create request.".to_string()));
-                        },
+                    if let Err(e) = stream_reply(&link, &message).await {
+                        error!("Failed to stream reply: {:?}", e);
+                        link.send_message(Msg::Error("Failed to get a reply. Please try again.".to_string()));
                     }
                 });
 
                 true
             }
-            Msg::Receive(message) => {
-                self.messages.push(message);
+            Msg::StreamDelta(delta) => {
+                if self.streaming {
+                    if let Some(last) = self.messages.last_mut() {
+                        last.message.push_str(&delta);
+                    }
+                } else {
+                    self.messages.push(ChatMessage {
+                        user: "Rusty".to_string(),
+                        message: delta,
+                    });
+                    self.streaming = true;
+                }
                 true
             }
+            Msg::StreamDone => {
+                self.streaming = false;
+                false
+            }
             Msg::UpdateInput(value) => {
                 self.input = value;
                 false
             }
             Msg::Error(error) => {
+                self.streaming = false;
                 self.messages.push(ChatMessage {
                     user: "System".to_string(),
                     message: error,
@@ -142,6 +143,58 @@ impl Component for Model {
     }
 }
 
+/// POSTs to `/api/chat/stream` and feeds each `data: ...` line of the SSE
+/// response to `link` as a `Msg::StreamDelta`, so `Model` can render the
+/// reply incrementally instead of waiting for the whole thing.
+async fn stream_reply(link: &Scope<Model>, message: &ChatMessage) -> Result<(), JsValue> {
+    let response = Request::post(&format!("{}/api/chat/stream", BASE_URL))
+        .header("Content-Type", "application/json")
+        .json(message)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?
+        .send()
+        .await
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    if !response.ok() {
+        return Err(JsValue::from_str(&format!("server error: {}", response.status())));
+    }
+
+    let body = response.body().ok_or_else(|| JsValue::from_str("response has no body"))?;
+    let reader: web_sys::ReadableStreamDefaultReader = body.get_reader().unchecked_into();
+    let decoder = TextDecoder::new()?;
+    let mut buf = String::new();
+
+    loop {
+        let result = JsFuture::from(reader.read()).await?;
+        let done = js_sys::Reflect::get(&result, &JsValue::from_str("done"))?
+            .as_bool()
+            .unwrap_or(true);
+        if done {
+            break;
+        }
+
+        let value = js_sys::Reflect::get(&result, &JsValue::from_str("value"))?;
+        let chunk: Uint8Array = value.unchecked_into();
+        buf.push_str(&decoder.decode_with_buffer_source(&chunk)?);
+
+        while let Some(pos) = buf.find("\n\n") {
+            let event = buf[..pos].to_string();
+            buf.drain(..pos + 2);
+
+            let Some(data) = event.strip_prefix("data: ") else {
+                continue;
+            };
+            if data == "[DONE]" {
+                link.send_message(Msg::StreamDone);
+            } else if let Ok(delta) = serde_json::from_str::<ChatMessage>(data) {
+                link.send_message(Msg::StreamDelta(delta.message));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[function_component(App)]
 pub fn app() -> Html {
     html! {
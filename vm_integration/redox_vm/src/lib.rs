@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VMCommand {
+    pub command: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VMResponse {
+    pub output: String,
+    pub error: Option<String>,
+}
+
+/// Subcommands the runner will execute. `execute_command` never goes near a
+/// shell: a first word not in this list is rejected before a process is
+/// ever spawned, and the remaining words (pipes or `;` included) are passed
+/// to the program as literal arguments, never interpreted.
+const ALLOWED_COMMANDS: &[&str] = &["ls", "pwd", "whoami", "date", "uptime"];
+
+pub async fn execute_command(command: &str) -> VMResponse {
+    let mut parts = command.split_whitespace();
+    let Some(program) = parts.next() else {
+        return VMResponse {
+            output: String::new(),
+            error: Some("no command given".to_string()),
+        };
+    };
+
+    if !ALLOWED_COMMANDS.contains(&program) {
+        return VMResponse {
+            output: String::new(),
+            error: Some(format!("command '{}' is not allowlisted", program)),
+        };
+    }
+
+    let args: Vec<&str> = parts.collect();
+    let output = Command::new(program).args(&args).output().await;
+
+    match output {
+        Ok(output) => {
+            if output.status.success() {
+                VMResponse {
+                    output: String::from_utf8_lossy(&output.stdout).to_string(),
+                    error: None,
+                }
+            } else {
+                VMResponse {
+                    output: String::new(),
+                    error: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+                }
+            }
+        }
+        Err(e) => VMResponse {
+            output: String::new(),
+            error: Some(e.to_string()),
+        },
+    }
+}